@@ -0,0 +1,116 @@
+//! Optional HTTP/2 support, layered onto the same listener as HTTP/1 (see
+//! [`crate::server::accept_connection`]). Enabled by the `http2` Cargo feature, which pulls in
+//! the `h2` and `http` crates.
+//!
+//! Each accepted h2 connection is driven to completion by [`serve`]: every stream is spawned as
+//! its own task as soon as it's accepted, so slow or long-lived streams don't hold up the rest
+//! of the connection's multiplexing the way awaiting each one inline would.
+//!
+//! `h2::server::handshake` expects a Tokio `AsyncRead`/`AsyncWrite`, while [`TcpStream`] here
+//! implements the `futures-lite` traits; [`FuturesAsyncReadCompatExt::compat`] bridges the two so
+//! the stream can be handed to `h2` as-is.
+
+use std::io;
+
+use bytes::{Bytes, BytesMut};
+use futures_lite::StreamExt;
+use h2::{
+    server::{self, SendResponse},
+    RecvStream,
+};
+use http::{Request as H2Request, Response as H2Response, StatusCode};
+use log::error;
+use my_async::{multi_thread::spawn, net::TcpStream};
+use tokio_util::compat::FuturesAsyncReadCompatExt;
+
+use crate::{
+    request,
+    response::{Response, ResponseBody},
+    server::HttpService,
+};
+
+/// The HTTP/2 connection preface every h2 client sends before any frames.
+pub(crate) const PREFACE: &[u8; 24] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+
+pub(crate) async fn serve<T>(stream: TcpStream, service: T) -> io::Result<()>
+where
+    T: HttpService + Send + Clone + 'static,
+{
+    let mut conn = server::handshake(stream.compat()).await.map_err(to_io_err)?;
+    while let Some(result) = conn.accept().await {
+        let (request, respond) = result.map_err(to_io_err)?;
+        let mut service = service.clone();
+        let _ = spawn(async move {
+            if let Err(e) = handle_stream(request, respond, &mut service).await {
+                error!("error serving h2 stream: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+async fn handle_stream<T: HttpService + Send>(
+    request: H2Request<RecvStream>,
+    mut respond: SendResponse<Bytes>,
+    service: &mut T,
+) -> io::Result<()> {
+    let (parts, mut recv_body) = request.into_parts();
+    let mut body = BytesMut::new();
+    while let Some(chunk) = recv_body.data().await {
+        let chunk = chunk.map_err(to_io_err)?;
+        let _ = recv_body.flow_control().release_capacity(chunk.len());
+        body.extend_from_slice(&chunk);
+    }
+    let headers = parts
+        .headers
+        .iter()
+        .map(|(name, val)| (name.as_str().to_owned(), val.as_bytes().to_vec()))
+        .collect::<Vec<_>>();
+    let path = parts
+        .uri
+        .path_and_query()
+        .map(|pq| pq.as_str())
+        .unwrap_or("/");
+    let req = request::from_parts(parts.method.as_str(), path, 2, headers, Some(body.freeze()));
+
+    let mut resp = Response::new();
+    if let Err(e) = service.call(req, &mut resp).await {
+        error!("error in h2 service: {}", e);
+        resp.status_code(500, &"Internal Server Error");
+        resp.body(e.to_string());
+    }
+
+    let mut builder = H2Response::builder().status(
+        StatusCode::from_u16(resp.status_code_value()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+    );
+    for (name, val) in resp.header_pairs() {
+        builder = builder.header(name, val);
+    }
+
+    match resp.take_body() {
+        ResponseBody::Buffered(buf) => {
+            let body = buf.freeze();
+            let end_of_stream = body.is_empty();
+            let response = builder.body(()).map_err(to_io_err)?;
+            let mut send = respond
+                .send_response(response, end_of_stream)
+                .map_err(to_io_err)?;
+            if !end_of_stream {
+                send.send_data(body, true).map_err(to_io_err)?;
+            }
+        }
+        ResponseBody::Stream(mut stream) => {
+            let response = builder.body(()).map_err(to_io_err)?;
+            let mut send = respond.send_response(response, false).map_err(to_io_err)?;
+            while let Some(chunk) = stream.next().await {
+                send.send_data(chunk?, false).map_err(to_io_err)?;
+            }
+            send.send_data(Bytes::new(), true).map_err(to_io_err)?;
+        }
+    }
+    Ok(())
+}
+
+fn to_io_err(e: impl std::fmt::Display) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e.to_string())
+}
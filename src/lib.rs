@@ -1,4 +1,6 @@
 mod date;
+#[cfg(feature = "http2")]
+mod h2;
 mod request;
 mod response;
 mod server;
@@ -10,5 +12,5 @@ pub mod re_export {
 
 pub use re_export::async_trait;
 pub use request::Request;
-pub use response::Response;
+pub use response::{BodySize, Response};
 pub use server::{HttpServer, HttpService, HttpServiceFactory};
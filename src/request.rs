@@ -1,6 +1,6 @@
-use std::{borrow::Cow, mem::MaybeUninit, slice};
+use std::{borrow::Cow, fmt, io, mem::MaybeUninit, slice, str};
 
-use bytes::{Bytes, BytesMut};
+use bytes::{Buf, Bytes, BytesMut};
 use httparse::{Status, EMPTY_HEADER};
 use log::error;
 
@@ -16,6 +16,7 @@ pub struct Request {
     body: Option<Bytes>,
     pub(crate) header_len: usize,
     pub(crate) body_len: usize,
+    pub(crate) chunked: bool,
 }
 
 /// An iterator that iterates over the headers of a request.
@@ -48,6 +49,31 @@ impl Request {
     pub fn body(&self) -> Option<&Bytes> {
         self.body.as_ref()
     }
+    /// Looks up a header by name, matching case-insensitively as HTTP permits (e.g.
+    /// `content-length` matches a header named `Content-Length`), returning its raw value.
+    pub fn header(&self, name: &str) -> Option<&[u8]> {
+        self.headers()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, v)| v)
+    }
+    /// Returns the parsed `Content-Length` header, if present and a valid integer.
+    pub fn content_length(&self) -> Option<usize> {
+        self.header("Content-Length")
+            .and_then(|v| str::from_utf8(v).ok())
+            .and_then(|v| v.trim().parse().ok())
+    }
+    /// Returns the `Content-Type` header value, if present and valid UTF-8.
+    pub fn content_type(&self) -> Option<&str> {
+        self.header("Content-Type")
+            .and_then(|v| str::from_utf8(v).ok())
+    }
+    /// Returns the comma-separated tokens of the `Connection` header (e.g. `keep-alive`,
+    /// `close`, `Upgrade`), if present and valid UTF-8.
+    pub fn connection(&self) -> Option<impl Iterator<Item = &str>> {
+        self.header("Connection")
+            .and_then(|v| str::from_utf8(v).ok())
+            .map(|v| v.split(',').map(str::trim))
+    }
     pub(crate) fn set_body(&mut self, body: Bytes) {
         self.body = Some(body);
     }
@@ -56,10 +82,41 @@ impl Request {
     }
 }
 
-pub fn decode(buf: &mut BytesMut) -> Result<Option<Request>, httparse::Error> {
+/// Errors `decode` can return, beyond the raw `httparse` parse failure.
+pub enum DecodeError {
+    /// The request line/headers themselves didn't parse.
+    Parse(httparse::Error),
+    /// The request carried both `Content-Length` and `Transfer-Encoding: chunked`, which
+    /// disagree on how to frame the body. Accepting either reading unconditionally is the
+    /// classic CL.TE request-smuggling shape when this server sits behind a front-end that
+    /// frames on the other header, so the request is rejected outright instead of picking one.
+    AmbiguousFraming,
+}
+
+impl From<httparse::Error> for DecodeError {
+    fn from(e: httparse::Error) -> Self {
+        DecodeError::Parse(e)
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::Parse(e) => write!(f, "{}", e),
+            DecodeError::AmbiguousFraming => write!(
+                f,
+                "request carried both Content-Length and Transfer-Encoding: chunked"
+            ),
+        }
+    }
+}
+
+pub fn decode(buf: &mut BytesMut) -> Result<Option<Request>, DecodeError> {
     let mut headers = [EMPTY_HEADER; 256];
     let mut req = httparse::Request::new(&mut headers);
     let mut body_len = 0;
+    let mut chunked = false;
+    let mut has_content_length = false;
     let status = req.parse(buf)?;
     let amt = match status {
         Status::Complete(amt) => amt,
@@ -72,15 +129,23 @@ pub fn decode(buf: &mut BytesMut) -> Result<Option<Request>, httparse::Error> {
     for (idx, h) in req.headers.iter().enumerate() {
         let name = h.name;
         let val = h.value;
-        if name == "Content-Length" {
+        if name.eq_ignore_ascii_case("Content-Length") {
+            has_content_length = true;
             body_len =
                 usize::from_str_radix(&String::from_utf8_lossy(val), 10).unwrap_or_else(|e| {
                     error!("Failed to parse Content-Length into integer: {}", e);
                     0
                 });
+        } else if name.eq_ignore_ascii_case("Transfer-Encoding") {
+            chunked = String::from_utf8_lossy(val)
+                .to_ascii_lowercase()
+                .contains("chunked");
         }
         headers[idx] = (to_data_range(name.as_bytes(), buf), to_data_range(val, buf));
     }
+    if chunked && has_content_length {
+        return Err(DecodeError::AmbiguousFraming);
+    }
     let header_len = req.headers.len();
     Ok(Some(Request {
         method: to_data_range(req.method.unwrap().as_bytes(), buf),
@@ -91,9 +156,172 @@ pub fn decode(buf: &mut BytesMut) -> Result<Option<Request>, httparse::Error> {
         body: None,
         body_len,
         header_len,
+        chunked,
     }))
 }
 
+/// Builds a `Request` from already-parsed parts rather than from a raw byte buffer via
+/// [`decode`]. Used by the h2 codepath, where `h2`/`http` have already parsed the request line
+/// and headers for us; the parts are copied into a `Request`-owned buffer so the rest of
+/// `Request` (which addresses its fields as [`DataRange`]s into one buffer) doesn't need to
+/// know the data didn't come from `httparse`.
+#[cfg(feature = "http2")]
+pub(crate) fn from_parts<I>(
+    method: &str,
+    path: &str,
+    version: u8,
+    headers: I,
+    body: Option<Bytes>,
+) -> Request
+where
+    I: IntoIterator<Item = (String, Vec<u8>)>,
+{
+    let mut data = BytesMut::new();
+    data.extend_from_slice(method.as_bytes());
+    let method_range: DataRange = (0, data.len());
+    let path_start = data.len();
+    data.extend_from_slice(path.as_bytes());
+    let path_range: DataRange = (path_start, data.len());
+
+    let mut headers_out: [(DataRange, DataRange); 256] = unsafe {
+        let h: [MaybeUninit<(DataRange, DataRange)>; 256] = MaybeUninit::uninit().assume_init();
+        std::mem::transmute(h)
+    };
+    let mut header_len = 0;
+    for (name, val) in headers {
+        if header_len >= headers_out.len() {
+            break;
+        }
+        let name_start = data.len();
+        data.extend_from_slice(name.as_bytes());
+        let name_range: DataRange = (name_start, data.len());
+        let val_start = data.len();
+        data.extend_from_slice(&val);
+        let val_range: DataRange = (val_start, data.len());
+        headers_out[header_len] = (name_range, val_range);
+        header_len += 1;
+    }
+
+    let body_len = body.as_ref().map(Bytes::len).unwrap_or(0);
+    Request {
+        method: method_range,
+        path: path_range,
+        version,
+        headers: headers_out,
+        data: data.freeze(),
+        body,
+        header_len,
+        body_len,
+        chunked: false,
+    }
+}
+
+/// Upper bound on a single chunk's declared size. A chunk-size line above it (e.g.
+/// `ffffffffffffffff`) is rejected as invalid data instead of the decoder waiting to buffer a
+/// payload of that size.
+const MAX_CHUNK_SIZE: usize = 8 * 1024 * 1024;
+
+/// Incremental decoder for a `Transfer-Encoding: chunked` request body.
+///
+/// Chunk-size lines may carry `;ext` parameters, which are ignored, and a chunk header or
+/// payload may be split across multiple reads, so [`ChunkedDecoder::decode`] buffers a partial
+/// chunk rather than mis-parsing it: it consumes as many complete chunks as `buf` currently
+/// holds and leaves anything incomplete in place for the next call.
+pub(crate) struct ChunkedDecoder {
+    body: BytesMut,
+    // size remaining to read for the chunk currently being consumed, `None` while waiting for
+    // the next chunk-size line.
+    remaining: Option<usize>,
+    // set once the zero-size chunk has been consumed, while its trailer section is still being read.
+    in_trailer: bool,
+}
+
+impl ChunkedDecoder {
+    pub(crate) fn new() -> Self {
+        Self {
+            body: BytesMut::new(),
+            remaining: None,
+            in_trailer: false,
+        }
+    }
+
+    /// Consumes complete chunks from the front of `buf`. Returns `Ok(true)` once the
+    /// terminating zero-size chunk (and the blank line ending its trailer section) has been
+    /// consumed, `Ok(false)` if `buf` ran out before the body was complete.
+    pub(crate) fn decode(&mut self, buf: &mut BytesMut) -> Result<bool, io::Error> {
+        if self.in_trailer {
+            return self.decode_trailer(buf);
+        }
+        loop {
+            match self.remaining {
+                None => {
+                    let Some(pos) = find_crlf(buf) else {
+                        return Ok(false);
+                    };
+                    let line = &buf[..pos];
+                    let size_str = match line.iter().position(|&b| b == b';') {
+                        Some(i) => &line[..i],
+                        None => line,
+                    };
+                    let size = str::from_utf8(size_str)
+                        .ok()
+                        .and_then(|s| usize::from_str_radix(s.trim(), 16).ok())
+                        .ok_or_else(|| {
+                            io::Error::new(io::ErrorKind::InvalidData, "invalid chunk size")
+                        })?;
+                    if size > MAX_CHUNK_SIZE {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "chunk size exceeds maximum",
+                        ));
+                    }
+                    buf.advance(pos + 2);
+                    if size == 0 {
+                        self.in_trailer = true;
+                        return self.decode_trailer(buf);
+                    }
+                    self.remaining = Some(size);
+                }
+                Some(size) => {
+                    // payload plus its trailing CRLF; checked to avoid overflowing into a
+                    // wrapped, too-small value that a later out-of-range slice would panic on.
+                    let needed = size.checked_add(2).ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidData, "chunk size overflow")
+                    })?;
+                    if buf.len() < needed {
+                        return Ok(false);
+                    }
+                    self.body.extend_from_slice(&buf[..size]);
+                    buf.advance(needed);
+                    self.remaining = None;
+                }
+            }
+        }
+    }
+
+    // Consumes the (possibly empty) trailer section following the zero-size chunk, up to and
+    // including the blank line that ends it.
+    fn decode_trailer(&mut self, buf: &mut BytesMut) -> Result<bool, io::Error> {
+        loop {
+            let Some(pos) = find_crlf(buf) else {
+                return Ok(false);
+            };
+            buf.advance(pos + 2);
+            if pos == 0 {
+                return Ok(true);
+            }
+        }
+    }
+
+    pub(crate) fn into_body(self) -> Bytes {
+        self.body.freeze()
+    }
+}
+
+fn find_crlf(buf: &[u8]) -> Option<usize> {
+    buf.windows(2).position(|w| w == b"\r\n")
+}
+
 fn to_data_range(s: &[u8], origin: &BytesMut) -> DataRange {
     let start = s.as_ptr() as usize - origin.as_ptr() as usize;
     debug_assert!(start < origin.len());
@@ -110,3 +338,123 @@ impl<'req> Iterator for RequestHeaders<'req> {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::ChunkedDecoder;
+    use bytes::BytesMut;
+
+    fn decode_all(decoder: &mut ChunkedDecoder, input: &[u8]) -> bool {
+        let mut buf = BytesMut::from(input);
+        decoder.decode(&mut buf).unwrap()
+    }
+
+    #[test]
+    fn decodes_a_complete_body_in_one_call() {
+        let mut decoder = ChunkedDecoder::new();
+        assert!(decode_all(&mut decoder, b"5\r\nhello\r\n0\r\n\r\n"));
+        assert_eq!(decoder.into_body().as_ref(), b"hello");
+    }
+
+    #[test]
+    fn ignores_chunk_extensions() {
+        let mut decoder = ChunkedDecoder::new();
+        assert!(decode_all(&mut decoder, b"5;foo=bar\r\nhello\r\n0;baz\r\n\r\n"));
+        assert_eq!(decoder.into_body().as_ref(), b"hello");
+    }
+
+    #[test]
+    fn parses_chunk_sizes_as_hex_not_decimal() {
+        let mut decoder = ChunkedDecoder::new();
+        // `a` is 10 in hex, not a valid decimal digit, so this also catches a radix-10 regression.
+        assert!(decode_all(&mut decoder, b"a\r\n0123456789\r\n0\r\n\r\n"));
+        assert_eq!(decoder.into_body().as_ref(), b"0123456789");
+
+        let mut decoder = ChunkedDecoder::new();
+        assert!(decode_all(&mut decoder, b"A\r\n0123456789\r\n0\r\n\r\n"));
+        assert_eq!(decoder.into_body().as_ref(), b"0123456789");
+    }
+
+    #[test]
+    fn buffers_a_chunk_payload_split_across_reads() {
+        let mut decoder = ChunkedDecoder::new();
+        let mut buf = BytesMut::from(&b"5\r\nhel"[..]);
+        // the chunk payload isn't fully buffered yet; must ask for more rather than mis-parse.
+        assert!(!decoder.decode(&mut buf).unwrap());
+        buf.extend_from_slice(b"lo\r\n0\r\n\r\n");
+        assert!(decoder.decode(&mut buf).unwrap());
+        assert_eq!(decoder.into_body().as_ref(), b"hello");
+    }
+
+    #[test]
+    fn buffers_a_chunk_size_line_split_across_reads() {
+        let mut decoder = ChunkedDecoder::new();
+        let mut buf = BytesMut::from(&b"5"[..]);
+        // not even the terminating CRLF of the chunk-size line has arrived yet.
+        assert!(!decoder.decode(&mut buf).unwrap());
+        buf.extend_from_slice(b"\r\nhello\r\n0\r\n\r\n");
+        assert!(decoder.decode(&mut buf).unwrap());
+        assert_eq!(decoder.into_body().as_ref(), b"hello");
+    }
+
+    #[test]
+    fn accumulates_multiple_chunks() {
+        let mut decoder = ChunkedDecoder::new();
+        assert!(decode_all(&mut decoder, b"3\r\nfoo\r\n3\r\nbar\r\n0\r\n\r\n"));
+        assert_eq!(decoder.into_body().as_ref(), b"foobar");
+    }
+
+    #[test]
+    fn rejects_an_oversized_chunk_size_instead_of_overflowing() {
+        let mut decoder = ChunkedDecoder::new();
+        let mut buf = BytesMut::from(&b"ffffffffffffffff\r\n"[..]);
+        assert!(decoder.decode(&mut buf).is_err());
+    }
+
+    fn decode_request(raw: &[u8]) -> super::Request {
+        let mut buf = BytesMut::from(raw);
+        super::decode(&mut buf).unwrap().unwrap()
+    }
+
+    #[test]
+    fn header_lookup_is_case_insensitive() {
+        let req = decode_request(b"GET / HTTP/1.1\r\nHost: example.com\r\n\r\n");
+        assert_eq!(req.header("host"), Some(b"example.com".as_slice()));
+        assert_eq!(req.header("HOST"), Some(b"example.com".as_slice()));
+        assert_eq!(req.header("Host"), Some(b"example.com".as_slice()));
+    }
+
+    #[test]
+    fn content_length_parses_a_valid_header() {
+        let req = decode_request(b"GET / HTTP/1.1\r\ncontent-length: 5\r\n\r\n");
+        assert_eq!(req.content_length(), Some(5));
+    }
+
+    #[test]
+    fn content_length_is_none_when_missing_or_malformed() {
+        let req = decode_request(b"GET / HTTP/1.1\r\n\r\n");
+        assert_eq!(req.content_length(), None);
+
+        let req = decode_request(b"GET / HTTP/1.1\r\nContent-Length: not-a-number\r\n\r\n");
+        assert_eq!(req.content_length(), None);
+    }
+
+    #[test]
+    fn content_type_returns_the_header_value() {
+        let req = decode_request(b"GET / HTTP/1.1\r\nContent-Type: text/plain\r\n\r\n");
+        assert_eq!(req.content_type(), Some("text/plain"));
+    }
+
+    #[test]
+    fn connection_splits_and_trims_multiple_tokens() {
+        let req = decode_request(b"GET / HTTP/1.1\r\nConnection: keep-alive, Upgrade\r\n\r\n");
+        let tokens: Vec<&str> = req.connection().unwrap().collect();
+        assert_eq!(tokens, vec!["keep-alive", "Upgrade"]);
+    }
+
+    #[test]
+    fn connection_is_none_when_header_missing() {
+        let req = decode_request(b"GET / HTTP/1.1\r\n\r\n");
+        assert!(req.connection().is_none());
+    }
+}
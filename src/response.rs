@@ -1,14 +1,54 @@
 use std::{
     borrow::Cow,
     fmt::{self, Write},
+    io,
+    pin::Pin,
 };
 
 use bytes::{BufMut, Bytes, BytesMut};
+use futures_lite::Stream;
 use httparse::Header;
 
+/// The size of a [`Response`]'s body, as reported by [`Response::body_size`]; a `Stream` body
+/// is encoded with chunked framing, any other with `Content-Length`.
+pub enum BodySize {
+    /// No body at all (e.g. `204 No Content`).
+    Empty,
+    /// A body of a known, fixed length, framed with `Content-Length`.
+    Sized(usize),
+    /// An open-ended body of unknown length, framed with `Transfer-Encoding: chunked`.
+    Stream,
+}
+
+/// A boxed stream of body chunks for a streaming [`Response`].
+pub type BoxBodyStream = Pin<Box<dyn Stream<Item = io::Result<Bytes>> + Send>>;
+
+/// Encodes the interim `100 Continue` status line sent in reply to a request carrying
+/// `Expect: 100-continue`, ahead of (and distinct from) the final [`Response::encode`] output.
+pub(crate) fn encode_continue() -> Bytes {
+    Bytes::from_static(b"HTTP/1.1 100 Continue\r\n\r\n")
+}
+
+pub(crate) enum ResponseBody {
+    Buffered(BytesMut),
+    Stream(BoxBodyStream),
+}
+
+/// The encoded form of a [`Response`], ready to be written to a connection.
+pub(crate) enum EncodedResponse {
+    /// A fully-buffered response: status line, headers and body in one buffer.
+    Full(Bytes),
+    /// A response whose body is streamed: the head (status line + headers, `Transfer-Encoding:
+    /// chunked`) is written first, then each item of `body` is written framed as a chunk.
+    Chunked {
+        head: Bytes,
+        body: BoxBodyStream,
+    },
+}
+
 pub struct Response<'a> {
     headers: Vec<Header<'a>>,
-    response: BytesMut,
+    body: ResponseBody,
     status_msg: StatusMsg<'a>,
 }
 
@@ -23,7 +63,7 @@ impl<'a> Response<'a> {
     pub fn new() -> Self {
         Self {
             headers: Vec::with_capacity(256),
-            response: BytesMut::with_capacity(4096),
+            body: ResponseBody::Buffered(BytesMut::with_capacity(4096)),
             status_msg: StatusMsg::Ok,
         }
     }
@@ -39,36 +79,105 @@ impl<'a> Response<'a> {
         self
     }
     pub fn body<T: AsRef<str>>(&mut self, s: T) -> &mut Self {
-        self.response.clear();
-        self.response.put(s.as_ref().as_bytes());
+        let mut buf = BytesMut::with_capacity(s.as_ref().len());
+        buf.put(s.as_ref().as_bytes());
+        self.body = ResponseBody::Buffered(buf);
         self
     }
     pub fn body_bytes<T: AsRef<[u8]>>(&mut self, s: T) -> &mut Self {
-        self.response.clear();
-        self.response.put(s.as_ref());
+        let mut buf = BytesMut::with_capacity(s.as_ref().len());
+        buf.put(s.as_ref());
+        self.body = ResponseBody::Buffered(buf);
+        self
+    }
+    /// Sets the body to a [`Stream`] of chunks rather than a single buffer.
+    ///
+    /// Use this for large files or SSE-style responses that shouldn't be fully buffered in
+    /// memory before being written out. The response is encoded with
+    /// `Transfer-Encoding: chunked` instead of `Content-Length`.
+    pub fn streaming_body<S>(&mut self, stream: S) -> &mut Self
+    where
+        S: Stream<Item = io::Result<Bytes>> + Send + 'static,
+    {
+        self.body = ResponseBody::Stream(Box::pin(stream));
         self
     }
-    pub(crate) fn encode(&self) -> Bytes {
-        let mut buf = BytesMut::with_capacity(4096);
-        let length = self.response.len();
+    /// Returns the [`BodySize`] of the body currently set on this response.
+    pub fn body_size(&self) -> BodySize {
+        match &self.body {
+            ResponseBody::Buffered(b) if b.is_empty() => BodySize::Empty,
+            ResponseBody::Buffered(b) => BodySize::Sized(b.len()),
+            ResponseBody::Stream(_) => BodySize::Stream,
+        }
+    }
+    /// Returns the numeric status code set on this response (used by the h2 codepath, which
+    /// needs it outside of the HTTP/1 `encode()` wire format).
+    #[cfg(feature = "http2")]
+    pub(crate) fn status_code_value(&self) -> u16 {
+        match &self.status_msg {
+            StatusMsg::Ok => 200,
+            StatusMsg::Custom(c, _) => *c as u16,
+        }
+    }
+    /// Returns the headers set on this response as plain `(name, value)` byte slices, for
+    /// codepaths (like h2) that build their own header representation instead of the HTTP/1
+    /// wire format `encode()` produces.
+    #[cfg(feature = "http2")]
+    pub(crate) fn header_pairs(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.headers.iter().map(|h| (h.name, h.value))
+    }
+    /// Takes the body set on this response, leaving an empty buffered body behind.
+    #[cfg(feature = "http2")]
+    pub(crate) fn take_body(&mut self) -> ResponseBody {
+        std::mem::replace(&mut self.body, ResponseBody::Buffered(BytesMut::new()))
+    }
+    fn encode_head<W: fmt::Write>(
+        &self,
+        w: &mut W,
+        transfer_encoding: Option<&str>,
+        content_length: Option<usize>,
+    ) {
         let now = crate::date::now();
-        write!(
-            FastWrite(&mut buf),
-            "HTTP/1.1 {}\r\nServer: Example\r\nContent-Length: {}\r\nDate: {}\r\n",
-            self.status_msg,
-            length,
-            now
-        )
-        .unwrap();
-        self.headers.iter().for_each(|h| {
-            buf.put(h.name.as_bytes());
-            buf.put(&b": "[..]);
-            buf.put(h.value);
-            buf.put(&b"\r\n"[..]);
-        });
-        buf.put("\r\n".as_bytes());
-        buf.put(self.response.as_ref());
-        buf.freeze()
+        write!(w, "HTTP/1.1 {}\r\nServer: Example\r\n", self.status_msg).unwrap();
+        if let Some(length) = content_length {
+            write!(w, "Content-Length: {}\r\n", length).unwrap();
+        }
+        if let Some(te) = transfer_encoding {
+            write!(w, "Transfer-Encoding: {}\r\n", te).unwrap();
+        }
+        write!(w, "Date: {}\r\n", now).unwrap();
+    }
+    pub(crate) fn encode(&mut self) -> EncodedResponse {
+        match std::mem::replace(&mut self.body, ResponseBody::Buffered(BytesMut::new())) {
+            ResponseBody::Buffered(body) => {
+                let mut buf = BytesMut::with_capacity(4096);
+                self.encode_head(&mut FastWrite(&mut buf), None, Some(body.len()));
+                self.headers.iter().for_each(|h| {
+                    buf.put(h.name.as_bytes());
+                    buf.put(&b": "[..]);
+                    buf.put(h.value);
+                    buf.put(&b"\r\n"[..]);
+                });
+                buf.put("\r\n".as_bytes());
+                buf.put(body.as_ref());
+                EncodedResponse::Full(buf.freeze())
+            }
+            ResponseBody::Stream(stream) => {
+                let mut buf = BytesMut::with_capacity(4096);
+                self.encode_head(&mut FastWrite(&mut buf), Some("chunked"), None);
+                self.headers.iter().for_each(|h| {
+                    buf.put(h.name.as_bytes());
+                    buf.put(&b": "[..]);
+                    buf.put(h.value);
+                    buf.put(&b"\r\n"[..]);
+                });
+                buf.put("\r\n".as_bytes());
+                EncodedResponse::Chunked {
+                    head: buf.freeze(),
+                    body: stream,
+                }
+            }
+        }
     }
 }
 
@@ -1,13 +1,13 @@
 use crate::{
     request::{self, Request},
-    response::Response,
+    response::{self, EncodedResponse, Response},
 };
 
 use std::{io, net::ToSocketAddrs};
 
 use async_trait::async_trait;
-use bytes::{Buf, BufMut, BytesMut};
-use futures_lite::{AsyncReadExt, AsyncWriteExt};
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures_lite::{AsyncReadExt, AsyncWriteExt, StreamExt};
 use log::{error, info};
 use my_async::{
     multi_thread::spawn,
@@ -76,7 +76,7 @@ pub trait HttpServiceFactory: Send + Sized + 'static {
                         info!("Received connection by: {}", addr);
                         let service = self.new_service();
                         // spawns handler for each connection.
-                        let h = spawn(handler(stream, service));
+                        let h = spawn(accept_connection(stream, service));
                         handles.push(h);
                     }
                     Err(e) => {
@@ -120,12 +120,81 @@ fn internal_error_resp<'a>(e: io::Error) -> Response<'a> {
     resp
 }
 
-async fn handler<T: HttpService + Send>(mut stream: TcpStream, service: T) -> io::Result<()> {
+fn bad_request_resp<'a>(msg: &str) -> Response<'a> {
+    let mut resp = Response::new();
+    resp.status_code(400, &"Bad Request").body(msg);
+    resp
+}
+
+/// Returns whether `req` carries an `Expect: 100-continue` header, meaning the client is
+/// waiting for an interim response before it streams the body.
+fn wants_continue(req: &Request) -> bool {
+    req.headers().any(|(name, val)| {
+        name.eq_ignore_ascii_case("Expect")
+            && std::str::from_utf8(val)
+                .map(|v| v.eq_ignore_ascii_case("100-continue"))
+                .unwrap_or(false)
+    })
+}
+
+/// Dispatches a freshly accepted connection to either the HTTP/2 or the HTTP/1 codepath.
+///
+/// With the `http2` feature enabled, the first bytes of the connection are peeked for the h2
+/// connection preface (`PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n`); since that read can't be undone, the
+/// peeked bytes are replayed into the HTTP/1 [`handler`] when they turn out not to be an h2
+/// client. The comparison happens one byte at a time so a short, complete HTTP/1 request (which
+/// never has anywhere near 24 bytes to send) isn't kept waiting for more input that isn't
+/// coming: as soon as a byte fails to match the preface, whatever was read so far is handed to
+/// `handler()` without reading any further. Without the feature, every connection is assumed to
+/// be HTTP/1, as before.
+///
+/// With the `http2` feature, an h2 connection spawns one task per stream (see
+/// [`crate::h2::serve`]), so the service is cloned once per stream the same way
+/// [`HttpServiceFactory::new_service`][a] clones it once per connection; this requires `T: Clone`
+/// in addition to the bounds used without the feature.
+///
+/// [a]: HttpServiceFactory::new_service
+#[cfg(feature = "http2")]
+async fn accept_connection<T: HttpService + Send + Clone + 'static>(
+    mut stream: TcpStream,
+    service: T,
+) -> io::Result<()> {
+    let mut preface = [0u8; 24];
+    let mut filled = 0;
+    while filled < preface.len() {
+        match stream.read(&mut preface[filled..filled + 1]).await {
+            Ok(0) => return Ok(()),
+            Ok(_) => {
+                filled += 1;
+                if preface[filled - 1] != crate::h2::PREFACE[filled - 1] {
+                    return handler(stream, service, &preface[..filled]).await;
+                }
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    crate::h2::serve(stream, service).await
+}
+
+/// Dispatches a freshly accepted connection to the HTTP/1 [`handler`]; every connection is
+/// assumed to be HTTP/1 when the `http2` feature is disabled.
+#[cfg(not(feature = "http2"))]
+async fn accept_connection<T: HttpService + Send>(stream: TcpStream, service: T) -> io::Result<()> {
+    handler(stream, service, &[]).await
+}
+
+async fn handler<T: HttpService + Send>(
+    mut stream: TcpStream,
+    service: T,
+    prefix: &[u8],
+) -> io::Result<()> {
     let mut bytes_pool: BytesMut = BytesMut::with_capacity(BYTES_POOL_SIZE);
-    let mut req_bytes_cnt: usize = 0;
+    bytes_pool.put(prefix);
+    let mut req_bytes_cnt: usize = prefix.len();
     // state for reading body.
     let mut reading_body = false;
     let mut remain_body_len: Option<usize> = None;
+    let mut chunked_decoder: Option<request::ChunkedDecoder> = None;
     let mut req_slot: Option<Request> = None;
     // read request
     loop {
@@ -139,6 +208,19 @@ async fn handler<T: HttpService + Send>(mut stream: TcpStream, service: T) -> io
                 }
                 advance(&mut bytes_pool, n);
 
+                // a chunked body isn't framed by a blank-line marker, so it's parsed on every
+                // read rather than gated behind the `section_end` check below.
+                if let Some(decoder) = chunked_decoder.as_mut() {
+                    if decoder.decode(&mut bytes_pool)? {
+                        let mut req = req_slot.take().unwrap();
+                        let body = chunked_decoder.take().unwrap().into_body();
+                        req.set_body(body);
+                        break process_and_write_response(stream, service, req).await;
+                    } else {
+                        continue;
+                    }
+                }
+
                 // section end mark, which is a blank line
                 let section_end =
                     &bytes_pool[req_bytes_cnt - 4..req_bytes_cnt] == &[13, 10, 13, 10];
@@ -146,7 +228,28 @@ async fn handler<T: HttpService + Send>(mut stream: TcpStream, service: T) -> io
                     if !reading_body {
                         match request::decode(&mut bytes_pool) {
                             Ok(Some(mut req)) => {
-                                if req.body_len > 0 {
+                                if req.chunked {
+                                    if wants_continue(&req) {
+                                        stream
+                                            .write_all(&response::encode_continue())
+                                            .await?;
+                                    }
+                                    let mut decoder = request::ChunkedDecoder::new();
+                                    if decoder.decode(&mut bytes_pool)? {
+                                        req.set_body(decoder.into_body());
+                                        break process_and_write_response(stream, service, req)
+                                            .await;
+                                    } else {
+                                        chunked_decoder = Some(decoder);
+                                        req_slot = Some(req);
+                                        continue;
+                                    }
+                                } else if req.body_len > 0 {
+                                    if wants_continue(&req) {
+                                        stream
+                                            .write_all(&response::encode_continue())
+                                            .await?;
+                                    }
                                     reading_body = true;
                                     let remain = req.body_len - bytes_pool.len();
                                     // check if we've already read the body.
@@ -170,6 +273,19 @@ async fn handler<T: HttpService + Send>(mut stream: TcpStream, service: T) -> io
                                 error!("Request should be completed but resolved as a partial request! Quit connection...");
                                 return Ok(());
                             }
+                            Err(request::DecodeError::AmbiguousFraming) => {
+                                error!(
+                                    "Request carried both Content-Length and Transfer-Encoding: chunked; rejecting"
+                                );
+                                let mut resp = bad_request_resp(
+                                    "ambiguous request framing: both Content-Length and \
+                                     Transfer-Encoding present",
+                                );
+                                if let EncodedResponse::Full(bytes) = resp.encode() {
+                                    write_all_bytes(&mut stream, bytes).await?;
+                                }
+                                return Ok(());
+                            }
                             Err(e) => {
                                 error!("Request parse error: {}", e);
                                 return Ok(());
@@ -196,18 +312,41 @@ async fn process_and_write_response<T: HttpService + Send>(
     req: Request,
 ) -> io::Result<()> {
     let mut resp = Response::new();
-    let mut resp_bytes = if let Err(e) = service.call(req, &mut resp).await {
+    let encoded = if let Err(e) = service.call(req, &mut resp).await {
         internal_error_resp(e).encode()
     } else {
         resp.encode()
     };
-    let mut left = resp_bytes.len();
+    match encoded {
+        EncodedResponse::Full(bytes) => write_all_bytes(&mut stream, bytes).await,
+        EncodedResponse::Chunked { head, mut body } => {
+            write_all_bytes(&mut stream, head).await?;
+            while let Some(chunk) = body.next().await {
+                let chunk = chunk?;
+                // An empty chunk would be framed as `0\r\n\r\n`, which is bit-for-bit the
+                // chunked-encoding terminator; skip it rather than ending the body early.
+                if chunk.is_empty() {
+                    continue;
+                }
+                let mut framed = BytesMut::with_capacity(chunk.len() + 32);
+                framed.put(format!("{:x}\r\n", chunk.len()).as_bytes());
+                framed.put(chunk);
+                framed.put(&b"\r\n"[..]);
+                write_all_bytes(&mut stream, framed.freeze()).await?;
+            }
+            write_all_bytes(&mut stream, Bytes::from_static(b"0\r\n\r\n")).await
+        }
+    }
+}
+
+async fn write_all_bytes(stream: &mut TcpStream, mut bytes: Bytes) -> io::Result<()> {
+    let mut left = bytes.len();
     while left > 0 {
-        match stream.write(&resp_bytes).await {
+        match stream.write(&bytes).await {
             Ok(0) => break,
             Ok(n) => {
                 left -= n;
-                resp_bytes.advance(n);
+                bytes.advance(n);
             }
             Err(e) => return Err(e),
         }